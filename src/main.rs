@@ -1,7 +1,9 @@
 use std::{collections::HashMap, io::{BufRead, BufReader, BufWriter, Read, Write}};
 
-use anyhow::{bail, Error};
+use anyhow::{anyhow, bail, Error};
 use argh::FromArgs;
+use regex::Regex;
+use serde_json::Value;
 
 const ENVVAR_DEFAULT_FORMAT: &str = "CSV2JSON_DEFAULT_FORMAT";
 const ENVVAR_INITIAL_VECTOR_CAPACITY: &str = "CSV2JSON_INITIAL_VECTOR_CAPACITY";
@@ -14,10 +16,13 @@ const FORMAT_NAME_MAP_OF_LISTS_SHORTENED: &str = "mol";
 
 const FORMAT_DEFAULT: &str = FORMAT_NAME_MAP_OF_LISTS;
 
+const DEFAULT_DELIMITER: char = ',';
+const DEFAULT_QUOTE: char = '"';
+
 #[derive(Clone)]
 enum CsvResult {
-    MapOfLists(HashMap<String, Vec<String>>),
-    ListOfMaps(Vec<HashMap<String, String>>)
+    MapOfLists(HashMap<String, Vec<Value>>),
+    ListOfMaps(Vec<HashMap<String, Value>>)
 }
 
 impl CsvResult {
@@ -45,6 +50,380 @@ impl CsvResult {
     }
 }
 
+/// The CSV dialect a given input is parsed with: which character separates
+/// fields, which character quotes a field, and whether field contents get
+/// trimmed of surrounding whitespace.
+struct Dialect {
+    delimiter: char,
+    quote: char,
+    trim: bool,
+}
+
+const COLUMN_TYPE_ANNOTATION_NUMBER: &str = "number";
+const COLUMN_TYPE_ANNOTATION_BOOLEAN: &str = "boolean";
+const COLUMN_TYPE_ANNOTATION_STRING: &str = "string";
+
+/// The type a column's cells are coerced to when `--infer-types` is active.
+/// `Auto` infers per-cell (int, then float, then bool, then null, then
+/// string); the other variants come from a MeiliSearch-style `name:type`
+/// header annotation and override inference for every cell in the column.
+#[derive(Clone, Copy, PartialEq)]
+enum ColumnType {
+    Auto,
+    Number,
+    Boolean,
+    String,
+}
+
+/// Splits a header token into its column name and declared type, e.g.
+/// `"age:number"` -> `("age", ColumnType::Number)`. Annotations are only
+/// recognized when `infer_types` is set; otherwise the token is taken
+/// verbatim as the column name, matching the tool's string-only default.
+fn parse_header_token(token: &str, infer_types: bool) -> (String, ColumnType) {
+    if infer_types {
+        if let Some((name, annotation)) = token.rsplit_once(':') {
+            let column_type = match annotation.to_lowercase().as_str() {
+                COLUMN_TYPE_ANNOTATION_NUMBER => Some(ColumnType::Number),
+                COLUMN_TYPE_ANNOTATION_BOOLEAN => Some(ColumnType::Boolean),
+                COLUMN_TYPE_ANNOTATION_STRING => Some(ColumnType::String),
+                _ => None,
+            };
+
+            if let Some(column_type) = column_type {
+                return (name.to_string(), column_type);
+            }
+        }
+    }
+
+    (token.to_string(), ColumnType::Auto)
+}
+
+/// Coerces a raw CSV cell into a `serde_json::Value` according to `column_type`.
+/// When `infer_types` is off, every cell is emitted as a JSON string, matching
+/// the tool's default behavior. An annotated column (`Number`/`Boolean`) that
+/// fails to coerce is either reported as an anomaly (falling back to a string)
+/// or rejected outright, depending on `allow_anomalies`.
+fn coerce_token(
+    token: &str,
+    column_type: ColumnType,
+    infer_types: bool,
+    allow_anomalies: bool,
+) -> Result<Value, Error> {
+    if !infer_types {
+        return Ok(Value::String(token.to_string()));
+    }
+
+    match column_type {
+        ColumnType::String => Ok(Value::String(token.to_string())),
+        ColumnType::Auto => Ok(infer_value(token)),
+        ColumnType::Number => {
+            if let Some(value) = parse_number(token) {
+                Ok(value)
+            }
+            else {
+                anomaly_or_string(token, "number", allow_anomalies)
+            }
+        },
+        ColumnType::Boolean => {
+            if let Some(value) = parse_bool(token) {
+                Ok(value)
+            }
+            else {
+                anomaly_or_string(token, "boolean", allow_anomalies)
+            }
+        },
+    }
+}
+
+fn anomaly_or_string(token: &str, expected_type: &str, allow_anomalies: bool) -> Result<Value, Error> {
+    let msg = format!("Could not coerce {:?} to {}", token, expected_type);
+    if allow_anomalies {
+        eprintln!("Warning: {}", msg);
+        Ok(Value::String(token.to_string()))
+    }
+    else {
+        bail!(msg)
+    }
+}
+
+/// Infers a JSON value for an unannotated cell: integer, then float, then
+/// case-insensitive boolean, then null for an empty cell, falling back to a
+/// plain string.
+fn infer_value(token: &str) -> Value {
+    if token.is_empty() {
+        return Value::Null;
+    }
+
+    if let Some(value) = parse_number(token) {
+        return value;
+    }
+
+    if let Some(value) = parse_bool(token) {
+        return value;
+    }
+
+    Value::String(token.to_string())
+}
+
+fn parse_number(token: &str) -> Option<Value> {
+    if let Ok(i) = token.parse::<i64>() {
+        Some(Value::from(i))
+    }
+    else if let Ok(f) = token.parse::<f64>() {
+        // `f64::from_str` accepts "NaN"/"inf"/"-inf", and `Value::from` on a
+        // non-finite float silently collapses to `Value::Null`. Reject those
+        // here so the token falls back to a plain string (or trips the
+        // annotated-column anomaly path) instead of becoming `null`.
+        if f.is_finite() {
+            Some(Value::from(f))
+        }
+        else {
+            None
+        }
+    }
+    else {
+        None
+    }
+}
+
+fn parse_bool(token: &str) -> Option<Value> {
+    match token.to_lowercase().as_str() {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => None,
+    }
+}
+
+/// A single `--where` condition: the header-mapped column it applies to, and
+/// how its value is tested.
+struct RowFilter {
+    column_index: usize,
+    matcher: FilterMatcher,
+}
+
+enum FilterMatcher {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl FilterMatcher {
+    fn is_match(&self, value: &str) -> bool {
+        match self {
+            FilterMatcher::Exact(expected) => value == expected,
+            FilterMatcher::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+/// The column selection/rename/filter rules for one run, built once from
+/// `Args` against the parsed header row and applied to every record
+/// thereafter, before it's materialized into the output shape.
+struct Projection {
+    /// Original column indices that survive `--select`, in output order.
+    surviving_indices: Vec<usize>,
+    /// Output header name per surviving index, after `--rename`.
+    output_names: HashMap<usize, String>,
+    /// `--where` conditions; a record must satisfy all of them to be kept.
+    filters: Vec<RowFilter>,
+}
+
+impl Projection {
+    fn build(
+        headers: &HashMap<usize, String>,
+        select: &Option<String>,
+        renames: &[String],
+        where_clauses: &[String],
+    ) -> Result<Projection, Error> {
+        let surviving_indices = if let Some(select) = select {
+            select.split(',')
+                .map(str::trim)
+                .map(|name| find_column_index(headers, name)
+                    .ok_or_else(|| anyhow!("Unknown column in --select: {}", name)))
+                .collect::<Result<Vec<_>, Error>>()?
+        }
+        else {
+            let mut indices: Vec<usize> = headers.keys().copied().collect();
+            indices.sort_unstable();
+            indices
+        };
+
+        let mut output_names: HashMap<usize, String> = surviving_indices.iter()
+            .map(|&i| (i, headers[&i].clone()))
+            .collect();
+
+        for rename in renames {
+            let (old, new) = rename.split_once('=')
+                .ok_or_else(|| anyhow!("Invalid --rename {:?}; expected \"old=new\"", rename))?;
+            let index = find_column_index(headers, old)
+                .ok_or_else(|| anyhow!("Unknown column in --rename: {}", old))?;
+            output_names.insert(index, new.to_string());
+        }
+
+        let filters = where_clauses.iter()
+            .map(|spec| parse_filter(spec, headers))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Projection { surviving_indices, output_names, filters })
+    }
+
+    fn keeps(&self, index: usize) -> bool {
+        self.surviving_indices.contains(&index)
+    }
+
+    fn output_name(&self, index: usize) -> &str {
+        self.output_names.get(&index).map(String::as_str).unwrap_or("")
+    }
+
+    fn matches(&self, tokens: &[String]) -> bool {
+        self.filters.iter().all(|filter| {
+            tokens.get(filter.column_index)
+                .map(|value| filter.matcher.is_match(value))
+                .unwrap_or(false)
+        })
+    }
+}
+
+fn find_column_index(headers: &HashMap<usize, String>, name: &str) -> Option<usize> {
+    headers.iter().find(|(_, header_name)| header_name.as_str() == name).map(|(&i, _)| i)
+}
+
+/// Parses a `--where` condition: `"col=value"` for an exact match, or
+/// `"col~regex"` for a regex match. When both `=` and `~` are present,
+/// whichever comes first in the string is the operator.
+fn parse_filter(spec: &str, headers: &HashMap<usize, String>) -> Result<RowFilter, Error> {
+    let eq_pos = spec.find('=');
+    let tilde_pos = spec.find('~');
+
+    let (column_name, rest, is_regex) = match (eq_pos, tilde_pos) {
+        (Some(eq), Some(tilde)) if tilde < eq => (&spec[..tilde], &spec[tilde + 1..], true),
+        (Some(eq), _) => (&spec[..eq], &spec[eq + 1..], false),
+        (None, Some(tilde)) => (&spec[..tilde], &spec[tilde + 1..], true),
+        (None, None) => bail!("Invalid --where filter {:?}; expected \"col=value\" or \"col~regex\"", spec),
+    };
+
+    let column_index = find_column_index(headers, column_name)
+        .ok_or_else(|| anyhow!("Unknown column in --where filter: {}", column_name))?;
+
+    let matcher = if is_regex {
+        FilterMatcher::Regex(Regex::new(rest)?)
+    }
+    else {
+        FilterMatcher::Exact(rest.to_string())
+    };
+
+    Ok(RowFilter { column_index, matcher })
+}
+
+/// The raw CLI inputs that only make sense once the header row is known:
+/// column selection/rename/filter (`--select`/`--rename`/`--where`) and
+/// strict schema validation (`--strict`/`--require`/`--unique`). Bundled
+/// together since both `Projection` and `SchemaValidator` are built from
+/// this once per input, right after the header line is parsed.
+struct QueryOptions<'a> {
+    select: &'a Option<String>,
+    rename: &'a [String],
+    where_clauses: &'a [String],
+    strict: bool,
+    require: &'a [String],
+    unique: &'a [String],
+}
+
+/// Strict schema validation: with `--strict`, every record must have
+/// exactly the header's column count; `--require`'d columns must be
+/// non-empty; `--unique`'d columns must not repeat a value. Violations are
+/// collected across the whole input and reported together at the end,
+/// rather than bailing on the first one, so a single pass surfaces every
+/// problem in a malformed export.
+struct SchemaValidator {
+    strict: bool,
+    expected_columns: usize,
+    required: Vec<(usize, String)>,
+    unique: Vec<(usize, String)>,
+    seen_unique_values: HashMap<String, HashMap<String, Vec<usize>>>,
+    violations: Vec<String>,
+}
+
+impl SchemaValidator {
+    fn build(headers: &HashMap<usize, String>, options: &QueryOptions) -> Result<SchemaValidator, Error> {
+        let required = options.require.iter()
+            .map(|name| resolve_named_column(headers, name, "--require"))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let unique = options.unique.iter()
+            .map(|name| resolve_named_column(headers, name, "--unique"))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(SchemaValidator {
+            strict: options.strict,
+            expected_columns: headers.len(),
+            required,
+            unique,
+            seen_unique_values: HashMap::new(),
+            violations: Vec::new(),
+        })
+    }
+
+    /// Checks one record against every active rule, recording a violation
+    /// message per problem found; `--unique` duplicates aren't reported
+    /// until `finish`, once every record has been seen.
+    fn validate_record(&mut self, tokens: &[String], line_number: usize) {
+        if self.strict && tokens.len() != self.expected_columns {
+            self.violations.push(format!(
+                "Line {}: expected {} columns, found {}", line_number, self.expected_columns, tokens.len()
+            ));
+        }
+
+        for (index, name) in &self.required {
+            if tokens.get(*index).map(|value| value.is_empty()).unwrap_or(true) {
+                self.violations.push(format!("Line {}: required column \"{}\" is empty", line_number, name));
+            }
+        }
+
+        for (index, name) in &self.unique {
+            if let Some(value) = tokens.get(*index) {
+                self.seen_unique_values
+                    .entry(name.clone())
+                    .or_default()
+                    .entry(value.clone())
+                    .or_default()
+                    .push(line_number);
+            }
+        }
+    }
+
+    /// Reports every violation collected, including `--unique` duplicates,
+    /// as a single error (or as warnings, with `--allow-anomalies`).
+    fn finish(mut self, allow_anomalies: bool) -> Result<(), Error> {
+        for (name, values) in &self.seen_unique_values {
+            for (value, lines) in values {
+                if lines.len() > 1 {
+                    self.violations.push(format!(
+                        "Column \"{}\" has duplicate value {:?} on lines {:?}", name, value, lines
+                    ));
+                }
+            }
+        }
+
+        if self.violations.is_empty() {
+            return Ok(());
+        }
+
+        let report = self.violations.join("\n");
+        if allow_anomalies {
+            eprintln!("Warning: {} schema violation(s) found:\n{}", self.violations.len(), report);
+            Ok(())
+        }
+        else {
+            bail!("{} schema violation(s) found:\n{}", self.violations.len(), report)
+        }
+    }
+}
+
+fn resolve_named_column(headers: &HashMap<usize, String>, name: &str, flag: &str) -> Result<(usize, String), Error> {
+    find_column_index(headers, name)
+        .map(|index| (index, name.to_string()))
+        .ok_or_else(|| anyhow!("Unknown column in {}: {}", flag, name))
+}
+
 #[derive(FromArgs)]
 /// CSV in; JSON out
 struct Args {
@@ -67,99 +446,543 @@ struct Args {
     /// pretty json output
     #[argh(switch, short = 'p', long = "pretty")]
     pretty: bool,
+
+    /// field delimiter character (default ',')
+    #[argh(option, short = 'd', long = "delimiter", default = "DEFAULT_DELIMITER")]
+    delimiter: char,
+
+    /// field quote character (default '"')
+    #[argh(option, long = "quote", default = "DEFAULT_QUOTE")]
+    quote: char,
+
+    /// trim leading/trailing whitespace from each field
+    #[argh(switch, long = "trim")]
+    trim: bool,
+
+    /// infer per-cell JSON types (integer/float/boolean/null) instead of emitting every value as a string
+    #[argh(switch, short = 't', long = "infer-types")]
+    infer_types: bool,
+
+    /// stream newline-delimited JSON objects as records are parsed, instead of buffering the whole
+    /// dataset; only valid for the "list-of-maps" format, and mutually exclusive with --pretty
+    #[argh(switch, long = "jsonl")]
+    jsonl: bool,
+
+    /// convert JSON back to CSV instead of CSV to JSON; auto-detected from the -i/-o file
+    /// extensions (.csv/.json) when omitted
+    #[argh(switch, short = 'r', long = "reverse")]
+    reverse: bool,
+
+    /// comma-separated list of columns to keep in the output (default: all columns)
+    #[argh(option, long = "select")]
+    select: Option<String>,
+
+    /// rename a header column; format "old=new" (repeatable)
+    #[argh(option, long = "rename")]
+    rename: Vec<String>,
+
+    /// keep only records whose field matches; "col=value" for an exact match or "col~regex" for
+    /// a regex match (repeatable; a record must satisfy every condition)
+    #[argh(option, long = "where")]
+    r#where: Vec<String>,
+
+    /// enforce that every record has exactly the header's column count; combine with --require
+    /// and --unique for a fully validated schema
+    #[argh(switch, long = "strict")]
+    strict: bool,
+
+    /// require a column to be non-empty in every record (repeatable)
+    #[argh(option, long = "require")]
+    require: Vec<String>,
+
+    /// require a column's values to be unique across all records (repeatable)
+    #[argh(option, long = "unique")]
+    unique: Vec<String>,
+}
+
+/// Which way a given invocation converts. Explicit `--reverse` always wins;
+/// otherwise this is sniffed from the `-i`/`-o` file extensions, falling
+/// back to the tool's original CSV-to-JSON behavior.
+enum Direction {
+    CsvToJson,
+    JsonToCsv,
+}
+
+fn resolve_direction(reverse: bool, input_file: &Option<String>, output_file: &Option<String>) -> Direction {
+    if reverse {
+        return Direction::JsonToCsv;
+    }
+
+    match input_file.as_deref().and_then(extension_of) {
+        Some(ext) if ext.eq_ignore_ascii_case("json") => return Direction::JsonToCsv,
+        Some(ext) if ext.eq_ignore_ascii_case("csv") => return Direction::CsvToJson,
+        _ => {},
+    }
+
+    match output_file.as_deref().and_then(extension_of) {
+        Some(ext) if ext.eq_ignore_ascii_case("csv") => return Direction::JsonToCsv,
+        Some(ext) if ext.eq_ignore_ascii_case("json") => return Direction::CsvToJson,
+        _ => {},
+    }
+
+    Direction::CsvToJson
+}
+
+fn extension_of(path: &str) -> Option<&str> {
+    std::path::Path::new(path).extension().and_then(|ext| ext.to_str())
 }
 
 fn main() -> Result<(), Error> {
     let args: Args = argh::from_env();
-    open_output(args.output_file)?
-        .write_all(
-        process_input(
-                open_input(args.input_file)?,
-                CsvResult::from_format_str(&args.format)?,
-                args.allow_anomalies,
-                args.pretty)?.as_bytes()
-            )?;
+    let dialect = Dialect {
+        delimiter: args.delimiter,
+        quote: args.quote,
+        trim: args.trim,
+    };
+
+    if args.jsonl && args.pretty {
+        bail!("--jsonl and --pretty are mutually exclusive");
+    }
+
+    match resolve_direction(args.reverse, &args.input_file, &args.output_file) {
+        Direction::JsonToCsv => {
+            if args.jsonl {
+                bail!("--jsonl only applies when converting CSV to JSON");
+            }
+
+            let mut input = open_input(args.input_file)?;
+            let mut json_str = String::new();
+            input.read_to_string(&mut json_str)?;
+
+            let (result, header_order) = parse_json_result(&json_str)?;
+            let mut output = open_output(args.output_file)?;
+            write_csv(&result, &header_order, &dialect, &mut output)?;
+            output.flush()?;
+        },
+        Direction::CsvToJson => {
+            let format = CsvResult::from_format_str(&args.format)?;
+
+            if args.jsonl {
+                if !matches!(format, CsvResult::ListOfMaps(_)) {
+                    bail!("--jsonl can only stream the \"list-of-maps\" format; \"map-of-lists\" requires the whole dataset up front");
+                }
+
+                let query = QueryOptions {
+                    select: &args.select,
+                    rename: &args.rename,
+                    where_clauses: &args.r#where,
+                    strict: args.strict,
+                    require: &args.require,
+                    unique: &args.unique,
+                };
+
+                let mut output = open_output(args.output_file)?;
+                process_input_jsonl(
+                    open_input(args.input_file)?,
+                    &dialect,
+                    args.allow_anomalies,
+                    args.infer_types,
+                    &query,
+                    &mut output,
+                )?;
+                output.flush()?;
+            }
+            else {
+                let query = QueryOptions {
+                    select: &args.select,
+                    rename: &args.rename,
+                    where_clauses: &args.r#where,
+                    strict: args.strict,
+                    require: &args.require,
+                    unique: &args.unique,
+                };
+
+                open_output(args.output_file)?
+                    .write_all(
+                    process_input(
+                            open_input(args.input_file)?,
+                            format,
+                            &dialect,
+                            args.allow_anomalies,
+                            args.infer_types,
+                            &query,
+                            args.pretty)?.as_bytes()
+                        )?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Parses a JSON document in either the list-of-maps or map-of-lists shape
+/// into a `CsvResult`, alongside the column order in which keys were first
+/// encountered — used to emit a stable CSV header row.
+fn parse_json_result(json_str: &str) -> Result<(CsvResult, Vec<String>), Error> {
+    let value: Value = serde_json::from_str(json_str)?;
+    match value {
+        Value::Array(items) => {
+            let mut list_of_maps = Vec::with_capacity(items.len());
+
+            for item in items {
+                let object = match item {
+                    Value::Object(object) => object,
+                    other => bail!("Expected each element of the JSON array to be an object, found: {}", other),
+                };
+
+                list_of_maps.push(object.into_iter().collect::<HashMap<String, Value>>());
+            }
+
+            let header_order = scan_first_seen_keys(json_str, 2);
+            Ok((CsvResult::ListOfMaps(list_of_maps), header_order))
+        },
+        Value::Object(columns) => {
+            let mut map_of_lists = HashMap::new();
+
+            for (column_name, column_values) in columns {
+                let column_values = match column_values {
+                    Value::Array(items) => items,
+                    other => bail!("Expected column \"{}\" to be a JSON array, found: {}", column_name, other),
+                };
+
+                map_of_lists.insert(column_name, column_values);
+            }
+
+            let header_order = scan_first_seen_keys(json_str, 1);
+            Ok((CsvResult::MapOfLists(map_of_lists), header_order))
+        },
+        other => bail!("Expected a JSON array (list-of-maps) or object (map-of-lists) at the document root, found: {}", other),
+    }
+}
+
+/// Scans the raw JSON text for the first-seen order of "record-level" keys
+/// — the keys of each object directly inside the root array (`target_depth`
+/// 2), or the keys of the root object itself (`target_depth` 1) — without
+/// relying on `serde_json::Map`'s iteration order, which is alphabetical
+/// unless the crate's `preserve_order` feature is enabled.
+fn scan_first_seen_keys(json_str: &str, target_depth: usize) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    let mut depth: usize = 0;
+    let mut in_string = false;
+    let mut escape = false;
+    let mut is_object_at_depth: Vec<bool> = Vec::new();
+    let mut last_string: Option<String> = None;
+    let mut current = String::new();
+
+    for c in json_str.chars() {
+        if in_string {
+            if escape {
+                escape = false;
+                current.push(c);
+            }
+            else if c == '\\' {
+                escape = true;
+            }
+            else if c == '"' {
+                in_string = false;
+                last_string = Some(std::mem::take(&mut current));
+            }
+            else {
+                current.push(c);
+            }
+            continue;
+        }
+
+        match c {
+            '"' => {
+                in_string = true;
+                current.clear();
+            },
+            '{' => {
+                depth += 1;
+                is_object_at_depth.push(true);
+                last_string = None;
+            },
+            '[' => {
+                depth += 1;
+                is_object_at_depth.push(false);
+                last_string = None;
+            },
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                is_object_at_depth.pop();
+                last_string = None;
+            },
+            ':' if depth == target_depth && is_object_at_depth.last() == Some(&true) => {
+                if let Some(key) = last_string.take() {
+                    if seen.insert(key.clone()) {
+                        keys.push(key);
+                    }
+                }
+            },
+            ':' => {},
+            ',' => {
+                last_string = None;
+            },
+            _ => {},
+        }
+    }
+
+    keys
+}
+
+/// Writes `result` out as RFC 4180 CSV, using `header_order` for the header
+/// row and for each record's field order.
+fn write_csv(result: &CsvResult, header_order: &[String], dialect: &Dialect, output: &mut impl Write) -> Result<(), Error> {
+    match result {
+        CsvResult::ListOfMaps(list_of_maps) => write_csv_list_of_maps(list_of_maps, header_order, dialect, output),
+        CsvResult::MapOfLists(map_of_lists) => write_csv_map_of_lists(map_of_lists, header_order, dialect, output),
+    }
+}
+
+fn write_csv_list_of_maps(
+    list_of_maps: &[HashMap<String, Value>],
+    header_order: &[String],
+    dialect: &Dialect,
+    output: &mut impl Write
+) -> Result<(), Error> {
+    write_csv_record(header_order, dialect, output)?;
+    for record in list_of_maps {
+        let fields: Vec<String> = header_order.iter()
+            .map(|header| record.get(header).map(value_to_field).unwrap_or_default())
+            .collect();
+        write_csv_record(&fields, dialect, output)?;
+    }
+
+    Ok(())
+}
+
+fn write_csv_map_of_lists(
+    map_of_lists: &HashMap<String, Vec<Value>>,
+    header_order: &[String],
+    dialect: &Dialect,
+    output: &mut impl Write
+) -> Result<(), Error> {
+    write_csv_record(header_order, dialect, output)?;
+
+    let row_count = header_order.iter()
+        .filter_map(|header| map_of_lists.get(header))
+        .map(|column| column.len())
+        .max()
+        .unwrap_or(0);
+
+    for i in 0..row_count {
+        let fields: Vec<String> = header_order.iter()
+            .map(|header| map_of_lists.get(header)
+                .and_then(|column| column.get(i))
+                .map(value_to_field)
+                .unwrap_or_default())
+            .collect();
+        write_csv_record(&fields, dialect, output)?;
+    }
+
+    Ok(())
+}
+
+fn write_csv_record(fields: &[String], dialect: &Dialect, output: &mut impl Write) -> Result<(), Error> {
+    let delimiter = dialect.delimiter.to_string();
+    let line = fields.iter()
+        .map(|field| format_csv_field(field, dialect))
+        .collect::<Vec<_>>()
+        .join(&delimiter);
+    writeln!(output, "{}", line)?;
+
+    Ok(())
+}
+
+/// Quotes a CSV field if it contains the delimiter, the quote character, or
+/// a newline, doubling any embedded quote characters.
+fn format_csv_field(field: &str, dialect: &Dialect) -> String {
+    let needs_quoting = field.contains(dialect.delimiter)
+        || field.contains(dialect.quote)
+        || field.contains('\n')
+        || field.contains('\r');
+
+    if needs_quoting {
+        let escaped_quote = format!("{0}{0}", dialect.quote);
+        let escaped = field.replace(dialect.quote, &escaped_quote);
+        format!("{0}{1}{0}", dialect.quote, escaped)
+    }
+    else {
+        field.to_string()
+    }
+}
+
+fn value_to_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// The per-column state built once a CSV input's header row has been parsed:
+/// column names, per-column types, the `--select`/`--rename`/`--where`
+/// projection, and the `--strict`/`--require`/`--unique` validator.
+type HeaderState = (HashMap<usize, String>, HashMap<usize, ColumnType>, Projection, SchemaValidator);
+
+/// Streams one compact JSON object per CSV record directly to `output` as each
+/// record is parsed, rather than accumulating a `Vec<HashMap<..>>` in memory
+/// first. Only applicable to the list-of-maps output shape.
+fn process_input_jsonl(
+    mut input: BufReader<Box<dyn Read>>,
+    dialect: &Dialect,
+    allow_anomalies: bool,
+    infer_types: bool,
+    query: &QueryOptions,
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    let mut headers: Option<HeaderState> = None;
+    let mut line_number: usize = 0;
+    while let Some(record) = read_record(&mut input, dialect) {
+        let tokens = record?;
+        line_number += 1;
+        if let Some((header_map, column_types, projection, validator)) = headers.as_mut() {
+            validator.validate_record(&tokens, line_number);
+            if let Some(row) = build_record_map(&tokens, header_map, column_types, projection, infer_types, allow_anomalies, query.strict)? {
+                serde_json::to_writer(&mut *output, &row)?;
+                output.write_all(b"\n")?;
+            }
+        }
+        else {
+            let (header_map, column_types) = process_headers(&tokens, infer_types);
+            let projection = Projection::build(&header_map, query.select, query.rename, query.where_clauses)?;
+            let validator = SchemaValidator::build(&header_map, query)?;
+            headers = Some((header_map, column_types, projection, validator));
+        }
+    }
+
+    if let Some((_, _, _, validator)) = headers {
+        validator.finish(allow_anomalies)?;
+    }
+
     Ok(())
 }
 
 fn process_input(
     mut input: BufReader<Box<dyn Read>>,
     mut result: CsvResult,
+    dialect: &Dialect,
     allow_anomalies: bool,
+    infer_types: bool,
+    query: &QueryOptions,
     pretty: bool
 ) -> Result<String, Error> {
-    let mut buffer = String::new();
-    let mut headers = None;
-    while let Some(line) = read_line(&mut input, &mut buffer) {
-        let tokens = line?.split(',');
-        if let Some(header_map) = headers.as_mut() {
+    let mut headers: Option<HeaderState> = None;
+    let mut line_number: usize = 0;
+    let policy = AnomalyPolicy { infer_types, allow_anomalies, strict: query.strict };
+    while let Some(record) = read_record(&mut input, dialect) {
+        let tokens = record?;
+        line_number += 1;
+        if let Some((header_map, column_types, projection, validator)) = headers.as_mut() {
+            validator.validate_record(&tokens, line_number);
             match &mut result {
                 CsvResult::MapOfLists(map_of_lists) => {
-                    process_line_for_map_of_lists(tokens, header_map, map_of_lists, allow_anomalies)?;
+                    process_line_for_map_of_lists(&tokens, header_map, column_types, projection, map_of_lists, &policy)?;
                 },
                 CsvResult::ListOfMaps(list_of_maps) => {
-                    process_line_for_list_of_maps(tokens, header_map, list_of_maps, allow_anomalies)?;
+                    process_line_for_list_of_maps(&tokens, header_map, column_types, projection, list_of_maps, &policy)?;
                 },
             }
         }
         else {
-            headers = Some(process_headers(tokens));
+            let (header_map, column_types) = process_headers(&tokens, infer_types);
+            let projection = Projection::build(&header_map, query.select, query.rename, query.where_clauses)?;
+            let validator = SchemaValidator::build(&header_map, query)?;
+            headers = Some((header_map, column_types, projection, validator));
         }
     }
 
+    if let Some((_, _, _, validator)) = headers {
+        validator.finish(allow_anomalies)?;
+    }
+
     to_json_str(&result, pretty)
 }
 
 fn to_json_str(result: &CsvResult, pretty: bool) -> Result<String, Error> {
-    let json_str;
-    if pretty {
-        json_str = format!("{}\n", match result {
+    let json_str = if pretty {
+        format!("{}\n", match result {
             CsvResult::MapOfLists(map_of_lists) => serde_json::to_string_pretty(map_of_lists)?,
             CsvResult::ListOfMaps(list_of_maps) => serde_json::to_string_pretty(list_of_maps)?,
-        });
+        })
     }
     else {
-        json_str = match result {
+        match result {
             CsvResult::MapOfLists(map_of_lists) => serde_json::to_string(map_of_lists)?,
             CsvResult::ListOfMaps(list_of_maps) => serde_json::to_string(list_of_maps)?,
         }
-    }
+    };
 
     Ok(json_str)
 }
 
-fn process_headers<'a>(tokens: impl Iterator<Item = &'a str>) -> HashMap<usize, String> {
-    let mut i: usize = 0;
-    let mut map = HashMap::new();
-    for token in tokens {
-        map.insert(i, token.replace("\n", ""));
-        i += 1;
+fn process_headers(tokens: &[String], infer_types: bool) -> (HashMap<usize, String>, HashMap<usize, ColumnType>) {
+    let mut headers = HashMap::new();
+    let mut column_types = HashMap::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let (name, column_type) = parse_header_token(token, infer_types);
+        headers.insert(i, name);
+        column_types.insert(i, column_type);
     }
 
-    map
+    (headers, column_types)
+}
+
+/// The anomaly-handling knobs shared by every per-record processing path:
+/// whether to infer per-cell types, whether malformed data is tolerated with
+/// a warning (`--allow-anomalies`) instead of rejected, and whether
+/// `--strict` column-count problems should be deferred to the
+/// `SchemaValidator`'s aggregated report instead of failing the row
+/// immediately. Bundled into one struct so these functions don't balloon
+/// into a long flag-per-parameter signature.
+struct AnomalyPolicy {
+    infer_types: bool,
+    allow_anomalies: bool,
+    strict: bool,
 }
 
-fn process_line_for_map_of_lists<'a>(
-    tokens: impl Iterator<Item = &'a str>,
+fn process_line_for_map_of_lists(
+    tokens: &[String],
     headers: &HashMap<usize, String>,
-    map_of_lists: &mut HashMap<String, Vec<String>>,
-    allow_anomalies: bool
+    column_types: &HashMap<usize, ColumnType>,
+    projection: &Projection,
+    map_of_lists: &mut HashMap<String, Vec<Value>>,
+    policy: &AnomalyPolicy,
 ) -> Result<(), Error> {
+    if !projection.matches(tokens) {
+        return Ok(());
+    }
+
     let mut i: usize = 0;
     for token in tokens {
-        if let Some(column_name) = headers.get(&i) {
-            if map_of_lists.get(column_name).is_none() {
-                map_of_lists.insert(column_name.clone(), Vec::with_capacity(get_initial_vec_capacity()));
-            }
+        if headers.get(&i).is_some() {
+            if projection.keeps(i) {
+                let output_name = projection.output_name(i);
+                if map_of_lists.get(output_name).is_none() {
+                    map_of_lists.insert(output_name.to_string(), Vec::with_capacity(get_initial_vec_capacity()));
+                }
 
-            let column: &mut Vec<String> = map_of_lists.get_mut(column_name).unwrap().as_mut();
-            column.push(token.replace("\n", ""));
+                let column_type = column_types.get(&i).copied().unwrap_or(ColumnType::Auto);
+                let value = coerce_token(token, column_type, policy.infer_types, policy.allow_anomalies)?;
+
+                let column: &mut Vec<Value> = map_of_lists.get_mut(output_name).unwrap();
+                column.push(value);
+            }
         }
         else {
             let msg = format!("Found item outside of expected bounds; index: {}", i);
-            if allow_anomalies {
+            if policy.allow_anomalies {
                 eprintln!("{}", msg);
             }
+            else if policy.strict {
+                // Already recorded as a column-count violation by the
+                // SchemaValidator; let it keep scanning so `finish()` can
+                // report every bad row in one pass instead of bailing here.
+            }
             else {
                 bail!(msg)
             }
@@ -170,9 +993,12 @@ fn process_line_for_map_of_lists<'a>(
 
     if i < headers.len() {
         let msg = format!("Line too short; length: {}; expected: {}", headers.len(), i);
-        if allow_anomalies {
+        if policy.allow_anomalies {
             eprintln!("Warning: {}", msg);
         }
+        else if policy.strict {
+            // See above: recorded by the SchemaValidator, not bailed here.
+        }
         else {
             bail!(msg)
         }
@@ -181,23 +1007,58 @@ fn process_line_for_map_of_lists<'a>(
     Ok(())
 }
 
-fn process_line_for_list_of_maps<'a>(
-    tokens: impl Iterator<Item = &'a str>,
+fn process_line_for_list_of_maps(
+    tokens: &[String],
     headers: &HashMap<usize, String>,
-    list_of_maps: &mut Vec<HashMap<String, String>>,
-    allow_anomalies: bool
+    column_types: &HashMap<usize, ColumnType>,
+    projection: &Projection,
+    list_of_maps: &mut Vec<HashMap<String, Value>>,
+    policy: &AnomalyPolicy,
 ) -> Result<(), Error> {
+    if let Some(map) = build_record_map(tokens, headers, column_types, projection, policy.infer_types, policy.allow_anomalies, policy.strict)? {
+        list_of_maps.push(map);
+    }
+
+    Ok(())
+}
+
+/// Coerces one CSV record into a single `column name -> value` map, the
+/// per-record unit both the buffered list-of-maps path and the `--jsonl`
+/// streaming path build on. Returns `None` when the record is dropped by a
+/// `--where` filter.
+fn build_record_map(
+    tokens: &[String],
+    headers: &HashMap<usize, String>,
+    column_types: &HashMap<usize, ColumnType>,
+    projection: &Projection,
+    infer_types: bool,
+    allow_anomalies: bool,
+    strict: bool
+) -> Result<Option<HashMap<String, Value>>, Error> {
+    if !projection.matches(tokens) {
+        return Ok(None);
+    }
+
     let mut map = HashMap::new();
     let mut i: usize = 0;
     for token in tokens {
-        if let Some(column_name) = headers.get(&i) {
-            map.insert(column_name.clone(), token.replace("\n", ""));
+        if headers.get(&i).is_some() {
+            if projection.keeps(i) {
+                let column_type = column_types.get(&i).copied().unwrap_or(ColumnType::Auto);
+                let value = coerce_token(token, column_type, infer_types, allow_anomalies)?;
+                map.insert(projection.output_name(i).to_string(), value);
+            }
         }
         else {
             let msg = format!("Found item outside of expected bounds; index: {}", i);
             if allow_anomalies {
                 eprintln!("Warning: {}", msg);
             }
+            else if strict {
+                // Already recorded as a column-count violation by the
+                // SchemaValidator; let it keep scanning so `finish()` can
+                // report every bad row in one pass instead of bailing here.
+            }
             else {
                 bail!(msg)
             }
@@ -210,30 +1071,144 @@ fn process_line_for_list_of_maps<'a>(
         if allow_anomalies {
             eprintln!("Warning: {}", msg);
         }
+        else if strict {
+            // See above: recorded by the SchemaValidator, not bailed here.
+        }
         else {
             bail!(msg)
         }
     }
 
-    list_of_maps.push(map);
-
-    Ok(())
+    Ok(Some(map))
 }
 
-fn read_line<'buf, T>(
+/// Reads one logical CSV record from `reader`, pulling in as many physical
+/// lines as necessary to close any quoted field left open by a prior line.
+/// Returns `None` once the input is exhausted.
+fn read_record<T>(
     reader: &mut BufReader<T>,
-    buffer: &'buf mut String,
-) -> Option<std::io::Result<&'buf mut String>> 
-where 
+    dialect: &Dialect,
+) -> Option<Result<Vec<String>, Error>>
+where
     T: ?Sized,
     T: Read
 {
-    buffer.clear();
+    let mut raw = String::new();
+    loop {
+        let bytes_read = match reader.read_line(&mut raw) {
+            Ok(n) => n,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        if bytes_read == 0 {
+            if raw.is_empty() {
+                return None;
+            }
+            if quote_is_open(&raw, dialect) {
+                return Some(Err(Error::msg(format!(
+                    "Unterminated quoted field at end of input: {}", raw
+                ))));
+            }
+            break;
+        }
+
+        if !quote_is_open(&raw, dialect) {
+            break;
+        }
+    }
+
+    Some(Ok(parse_record(&raw, dialect)))
+}
+
+/// Determines whether, after scanning all of `s`, a quoted field has been
+/// opened but not yet closed (accounting for `""` as an escaped quote).
+/// Mirrors `parse_record`'s rule that a quote only opens a field when it
+/// appears at that field's start, so a bare quote stranded inside an
+/// otherwise-unquoted field isn't mistaken for an open quote.
+fn quote_is_open(s: &str, dialect: &Dialect) -> bool {
+    let mut in_quotes = false;
+    let mut field_started = false;
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == dialect.quote {
+                if chars.peek() == Some(&dialect.quote) {
+                    chars.next();
+                }
+                else {
+                    in_quotes = false;
+                }
+            }
+        }
+        else if c == dialect.quote && !field_started {
+            in_quotes = true;
+            field_started = true;
+        }
+        else if c == dialect.delimiter {
+            field_started = false;
+        }
+        else if c == '\n' || c == '\r' {
+            // Bare line terminator outside of a quoted field; record boundary.
+        }
+        else {
+            field_started = true;
+        }
+    }
+
+    in_quotes
+}
+
+/// Tokenizes a (possibly multi-line) raw CSV record into fields, honoring
+/// quoted fields, `""`-escaped quotes, and embedded newlines.
+fn parse_record(raw: &str, dialect: &Dialect) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == dialect.quote {
+                if chars.peek() == Some(&dialect.quote) {
+                    field.push(dialect.quote);
+                    chars.next();
+                }
+                else {
+                    in_quotes = false;
+                }
+            }
+            else {
+                field.push(c);
+            }
+        }
+        else if c == dialect.quote && field.is_empty() {
+            in_quotes = true;
+        }
+        else if c == dialect.delimiter {
+            fields.push(finish_field(field, dialect.trim));
+            field = String::new();
+        }
+        else if c == '\n' || c == '\r' {
+            // Bare line terminator outside of a quoted field; record boundary.
+        }
+        else {
+            field.push(c);
+        }
+    }
+
+    fields.push(finish_field(field, dialect.trim));
+
+    fields
+}
 
-    reader
-        .read_line(buffer)
-        .map(|u| if u == 0 { None } else { Some(buffer) })
-        .transpose()
+fn finish_field(field: String, trim: bool) -> String {
+    if trim {
+        field.trim().to_string()
+    }
+    else {
+        field
+    }
 }
 
 fn open_input(input_file: Option<String>) -> Result<BufReader<Box<dyn Read>>, Error> {
@@ -280,6 +1255,91 @@ fn get_initial_vec_capacity() -> usize {
                 ENVVAR_INITIAL_VECTOR_CAPACITY, val, DEFAULT);
         }
     }
-    
+
     DEFAULT
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dialect() -> Dialect {
+        Dialect { delimiter: ',', quote: '"', trim: false }
+    }
+
+    #[test]
+    fn read_record_handles_quoted_field_with_embedded_newline() {
+        let raw = "1,\"multi\nline\",3\n";
+        let mut reader = BufReader::new(raw.as_bytes());
+        let record = read_record(&mut reader, &dialect())
+            .expect("a record")
+            .expect("no error");
+
+        assert_eq!(record, vec!["1", "multi\nline", "3"]);
+    }
+
+    #[test]
+    fn read_record_does_not_over_consume_on_bare_quote_in_unquoted_field() {
+        let raw = "alice,5\"6 tall\nbob,6ft\n";
+        let mut reader = BufReader::new(raw.as_bytes());
+
+        let first = read_record(&mut reader, &dialect()).expect("a record").expect("no error");
+        assert_eq!(first, vec!["alice", "5\"6 tall"]);
+
+        let second = read_record(&mut reader, &dialect()).expect("a record").expect("no error");
+        assert_eq!(second, vec!["bob", "6ft"]);
+    }
+
+    #[test]
+    fn coerce_token_applies_number_annotation() {
+        let value = coerce_token("42", ColumnType::Number, true, false).expect("coerces");
+        assert_eq!(value, Value::from(42));
+
+        let err = coerce_token("not-a-number", ColumnType::Number, true, false);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn parse_number_rejects_non_finite_floats() {
+        assert!(parse_number("NaN").is_none());
+        assert!(parse_number("inf").is_none());
+        assert!(parse_number("-inf").is_none());
+        assert_eq!(parse_number("1.5"), Some(Value::from(1.5)));
+    }
+
+    #[test]
+    fn where_regex_filter_drops_non_matching_rows() {
+        let mut headers = HashMap::new();
+        headers.insert(0, "name".to_string());
+        headers.insert(1, "age".to_string());
+
+        let where_clauses = vec!["name~^a".to_string()];
+        let projection = Projection::build(&headers, &None, &[], &where_clauses).expect("builds");
+
+        assert!(projection.matches(&["alice".to_string(), "30".to_string()]));
+        assert!(!projection.matches(&["bob".to_string(), "40".to_string()]));
+    }
+
+    #[test]
+    fn unique_validator_reports_duplicate_values() {
+        let mut headers = HashMap::new();
+        headers.insert(0, "id".to_string());
+
+        let unique = vec!["id".to_string()];
+        let options = QueryOptions {
+            select: &None,
+            rename: &[],
+            where_clauses: &[],
+            strict: false,
+            require: &[],
+            unique: &unique,
+        };
+
+        let mut validator = SchemaValidator::build(&headers, &options).expect("builds");
+        validator.validate_record(&["1".to_string()], 1);
+        validator.validate_record(&["1".to_string()], 2);
+
+        let err = validator.finish(false).expect_err("duplicate should fail");
+        assert!(err.to_string().contains("duplicate value"));
+    }
+}